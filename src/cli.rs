@@ -0,0 +1,121 @@
+use config::{Config, Credentials};
+use rand::RngCore;
+use repo::UpdateError;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A throwaway `GIT_ASKPASS`/`SSH_ASKPASS` helper.
+///
+/// `git` invokes the program named by `GIT_ASKPASS` whenever it needs a password
+/// or passphrase and prints the prompt on argv; the program is expected to write
+/// the secret to stdout. We drop a tiny script that echoes a secret taken from
+/// its own environment, then hand that same secret to `git` through the child's
+/// environment so it never touches the command line or a TTY. The script is
+/// removed when the `Askpass` is dropped.
+pub struct Askpass {
+    script: Option<PathBuf>,
+    secret: Option<String>,
+}
+
+impl Askpass {
+    /// Build an askpass helper for the repo's credentials, or `None` worth of
+    /// state when no secret needs delivering.
+    pub fn new(config: &Config) -> Result<Self, UpdateError> {
+        let secret = match config.credentials {
+            Some(Credentials::Password(ref password)) => password.password.clone(),
+            Some(Credentials::SSH(ref ssh)) => ssh.passphrase.clone(),
+            None => None,
+        };
+
+        let script = match secret {
+            Some(_) => {
+                // Create with O_EXCL and an unpredictable name so we never follow a
+                // pre-planted symlink or clobber an attacker's file — the helper is
+                // an executable that will read a secret from its environment. The
+                // mode is set at open time rather than after, closing the window
+                // where the script would be world-readable.
+                let mut nonce = [0u8; 16];
+                rand::rngs::OsRng.fill_bytes(&mut nonce);
+                let path: PathBuf = env::temp_dir()
+                    .join(format!("pullomatic-askpass-{}", hex(&nonce)));
+
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o700)
+                    .open(&path)?;
+                writeln!(file, "#!/bin/sh\nprintf '%s' \"$PULLOMATIC_ASKPASS_SECRET\"")?;
+                Some(path)
+            }
+            None => None,
+        };
+
+        return Ok(Self { script, secret });
+    }
+
+    fn as_ref(&self) -> Option<&Askpass> {
+        return Some(self);
+    }
+}
+
+impl Drop for Askpass {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.script {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Run `git` in `path` and return its stdout, mapping failure into `UpdateError`.
+pub fn git(path: &Path, config: &Config, args: &[&str]) -> Result<String, UpdateError> {
+    return git_with_askpass(path, config, None, args);
+}
+
+/// As `git`, but wiring up the askpass helper so credentials are delivered
+/// non-interactively.
+pub fn git_with_askpass(path: &Path,
+                        _config: &Config,
+                        askpass: Option<&Askpass>,
+                        args: &[&str]) -> Result<String, UpdateError> {
+    let mut command = Command::new("git");
+    command.current_dir(path)
+           .args(args)
+           .env("GIT_TERMINAL_PROMPT", "0");
+
+    if let Some(askpass) = askpass {
+        if let Some(ref script) = askpass.script {
+            command.env("GIT_ASKPASS", script)
+                   .env("SSH_ASKPASS", script)
+                   // SSH_ASKPASS is only consulted when there is no controlling
+                   // terminal and DISPLAY is set, so provide a dummy one.
+                   .env("DISPLAY", ":0");
+            if let Some(ref secret) = askpass.secret {
+                command.env("PULLOMATIC_ASKPASS_SECRET", secret);
+            }
+        }
+    }
+
+    let output = command.output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        let code = output.status.code()
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| "signal".to_owned());
+        return Err(UpdateError::Cli(format!("git {} exited {}: {}", args[0], code, stderr)));
+    }
+
+    return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+}
+
+/// Lower-case hex encoding, used to build an unpredictable temp-file name.
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        out.push_str(&format!("{:02x}", byte));
+    }
+    return out;
+}
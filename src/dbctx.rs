@@ -0,0 +1,129 @@
+use rusqlite::{Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The persisted view of a repo's state, keyed by repo name.
+#[derive(Debug, Clone)]
+pub struct StoredState {
+    pub last_checked: Option<SystemTime>,
+    pub last_changed: Option<SystemTime>,
+    pub remote_oid: Option<String>,
+}
+
+/// A handle to the SQLite store shared by every repo.
+///
+/// Follows the dbctx/sql pattern used by the CI daemons: one connection behind a
+/// `Mutex`, a single `migrate()` at construction, and small typed accessors for
+/// the rest of the program. Timestamps are stored as unix-epoch seconds because
+/// `Instant` is meaningless across a process restart.
+#[derive(Debug)]
+pub struct DbCtx {
+    conn: Mutex<Connection>,
+}
+
+impl DbCtx {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, rusqlite::Error> {
+        let ctx = Self { conn: Mutex::new(Connection::open(path)?) };
+        ctx.migrate()?;
+        return Ok(ctx);
+    }
+
+    fn migrate(&self) -> Result<(), rusqlite::Error> {
+        self.conn.lock().unwrap().execute_batch(
+            "CREATE TABLE IF NOT EXISTS repo_state (
+                 name         TEXT PRIMARY KEY,
+                 last_checked INTEGER,
+                 last_changed INTEGER,
+                 remote_oid   TEXT
+             );")?;
+        return Ok(());
+    }
+
+    /// Hydrate the stored state for a repo, if any row exists.
+    pub fn load(&self, name: &str) -> Result<Option<StoredState>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        return conn.query_row(
+            "SELECT last_checked, last_changed, remote_oid FROM repo_state WHERE name = ?1",
+            [name],
+            |row| Ok(StoredState {
+                last_checked: row.get::<_, Option<i64>>(0)?.map(from_epoch),
+                last_changed: row.get::<_, Option<i64>>(1)?.map(from_epoch),
+                remote_oid: row.get(2)?,
+            }),
+        ).optional();
+    }
+
+    /// Write the state back after an `update()`.
+    pub fn store(&self,
+                 name: &str,
+                 state: &StoredState) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO repo_state (name, last_checked, last_changed, remote_oid)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT(name) DO UPDATE SET
+                     last_checked = excluded.last_checked,
+                     last_changed = excluded.last_changed,
+                     remote_oid   = excluded.remote_oid;",
+            rusqlite::params![
+                name,
+                state.last_checked.map(to_epoch),
+                state.last_changed.map(to_epoch),
+                state.remote_oid,
+            ],
+        )?;
+        return Ok(());
+    }
+}
+
+fn to_epoch(time: SystemTime) -> i64 {
+    return time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+}
+
+fn from_epoch(secs: i64) -> SystemTime {
+    return UNIX_EPOCH + Duration::from_secs(secs as u64);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::DbCtx;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn upsert_round_trips_and_overwrites() {
+        let db = DbCtx::new(":memory:").unwrap();
+
+        // Nothing stored yet.
+        assert!(db.load("repo").unwrap().is_none());
+
+        let first = super::StoredState {
+            last_checked: Some(UNIX_EPOCH + Duration::from_secs(100)),
+            last_changed: None,
+            remote_oid: Some("aaaa".to_owned()),
+        };
+        db.store("repo", &first).unwrap();
+
+        let loaded = db.load("repo").unwrap().unwrap();
+        assert_eq!(loaded.last_checked, first.last_checked);
+        assert_eq!(loaded.last_changed, None);
+        assert_eq!(loaded.remote_oid.as_deref(), Some("aaaa"));
+
+        // A second store for the same name must update the existing row, not
+        // insert a duplicate (the ON CONFLICT upsert).
+        let second = super::StoredState {
+            last_checked: Some(UNIX_EPOCH + Duration::from_secs(200)),
+            last_changed: Some(UNIX_EPOCH + Duration::from_secs(200)),
+            remote_oid: Some("bbbb".to_owned()),
+        };
+        db.store("repo", &second).unwrap();
+
+        let loaded = db.load("repo").unwrap().unwrap();
+        assert_eq!(loaded.last_checked, second.last_checked);
+        assert_eq!(loaded.last_changed, second.last_changed);
+        assert_eq!(loaded.remote_oid.as_deref(), Some("bbbb"));
+    }
+}
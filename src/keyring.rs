@@ -0,0 +1,277 @@
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use config::Credentials;
+use rand::RngCore;
+use serde_json;
+use std::collections::HashMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Number of bcrypt-pbkdf rounds used when sealing a fresh credential.
+const DEFAULT_ROUNDS: u32 = 16;
+
+/// A single credential sealed at rest.
+///
+/// The plaintext `Credentials` is serialized to JSON and encrypted with
+/// AES-256-GCM under a key derived from the master passphrase via bcrypt-pbkdf.
+/// The per-credential salt, round count and GCM nonce are stored in the clear
+/// next to the ciphertext so the same passphrase can reopen it later.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SealedCredential {
+    pub id: String,
+    pub salt: Vec<u8>,
+    pub rounds: u32,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// The set of credentials decrypted into memory after a successful unlock.
+#[derive(Debug, Default)]
+pub struct UnlockedKeyring {
+    credentials: HashMap<String, Credentials>,
+}
+
+#[derive(Debug)]
+pub enum KeyringError {
+    Io(io::Error),
+    Serde(serde_json::Error),
+    /// The passphrase was wrong or the ciphertext was tampered with.
+    Crypto,
+    /// No credential with the requested id exists in the keyring.
+    NotFound(String),
+    /// The `keyring` subcommand was invoked with bad arguments.
+    Usage(String),
+}
+
+impl fmt::Display for KeyringError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            KeyringError::Io(ref err) => write!(f, "IO error: {}", err),
+            KeyringError::Serde(ref err) => write!(f, "serialization error: {}", err),
+            KeyringError::Crypto => write!(f, "could not decrypt credential (wrong passphrase?)"),
+            KeyringError::NotFound(ref id) => write!(f, "no such credential: {}", id),
+            KeyringError::Usage(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl error::Error for KeyringError {
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match *self {
+            KeyringError::Io(ref err) => Some(err),
+            KeyringError::Serde(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for KeyringError {
+    fn from(err: io::Error) -> Self { KeyringError::Io(err) }
+}
+
+impl From<serde_json::Error> for KeyringError {
+    fn from(err: serde_json::Error) -> Self { KeyringError::Serde(err) }
+}
+
+impl SealedCredential {
+    /// Seal `credentials` under `passphrase`, generating a fresh salt and nonce.
+    pub fn seal(id: String,
+                credentials: &Credentials,
+                passphrase: &str) -> Result<Self, KeyringError> {
+        let plaintext = serde_json::to_vec(credentials)?;
+        return Self::seal_bytes(id, &plaintext, passphrase);
+    }
+
+    /// Decrypt this credential with `passphrase`.
+    pub fn unseal(&self, passphrase: &str) -> Result<Credentials, KeyringError> {
+        let plaintext = self.unseal_bytes(passphrase)?;
+        return Ok(serde_json::from_slice(&plaintext)?);
+    }
+
+    /// Seal raw bytes — the crypto core behind `seal`, factored out so it can be
+    /// exercised without constructing a `Credentials`.
+    fn seal_bytes(id: String,
+                  plaintext: &[u8],
+                  passphrase: &str) -> Result<Self, KeyringError> {
+        let mut salt = vec![0u8; 16];
+        let mut nonce = vec![0u8; 12];
+        rand::rngs::OsRng.fill_bytes(&mut salt);
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+        let key = derive_key(passphrase, &salt, DEFAULT_ROUNDS)?;
+        let cipher = Aes256Gcm::new(Key::from_slice(&key));
+
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| KeyringError::Crypto)?;
+
+        return Ok(Self { id, salt, rounds: DEFAULT_ROUNDS, nonce, ciphertext });
+    }
+
+    /// Decrypt to raw bytes — the crypto core behind `unseal`.
+    fn unseal_bytes(&self, passphrase: &str) -> Result<Vec<u8>, KeyringError> {
+        let key = derive_key(passphrase, &self.salt, self.rounds)?;
+        let cipher = Aes256Gcm::new(Key::from_slice(&key));
+
+        return cipher.decrypt(Nonce::from_slice(&self.nonce), self.ciphertext.as_ref())
+            .map_err(|_| KeyringError::Crypto);
+    }
+}
+
+/// Load the sealed keyring file, returning an empty set if it does not exist yet.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<SealedCredential>, KeyringError> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let file = File::open(path)?;
+    return Ok(serde_json::from_reader(file)?);
+}
+
+/// Write the sealed keyring back to disk.
+pub fn save<P: AsRef<Path>>(path: P,
+                            sealed: &[SealedCredential]) -> Result<(), KeyringError> {
+    let json = serde_json::to_vec_pretty(sealed)?;
+    fs::write(path, json)?;
+    return Ok(());
+}
+
+/// Unlock every sealed credential in `path` into memory with `passphrase`.
+pub fn unlock<P: AsRef<Path>>(path: P,
+                              passphrase: &str) -> Result<UnlockedKeyring, KeyringError> {
+    let mut credentials = HashMap::new();
+    for sealed in load(path)? {
+        let cred = sealed.unseal(passphrase)?;
+        credentials.insert(sealed.id, cred);
+    }
+
+    return Ok(UnlockedKeyring { credentials });
+}
+
+impl UnlockedKeyring {
+    /// Look up a decrypted credential by the id `Config` references.
+    pub fn get(&self, id: &str) -> Option<&Credentials> {
+        return self.credentials.get(id);
+    }
+}
+
+/// Add a new credential or rotate an existing one in the sealed keyring.
+///
+/// Backs the `keyring` CLI subcommand so operators never write plaintext keys
+/// into the daemon config: an existing entry with the same id is replaced.
+pub fn add_or_rotate<P: AsRef<Path>>(path: P,
+                                     passphrase: &str,
+                                     id: String,
+                                     credentials: &Credentials) -> Result<(), KeyringError> {
+    let mut sealed = load(&path)?;
+    sealed.retain(|entry| entry.id != id);
+    sealed.push(SealedCredential::seal(id, credentials, passphrase)?);
+    return save(&path, &sealed);
+}
+
+/// Entry point for the `keyring` subcommand, dispatched from `main`.
+///
+/// Usage: `pullomatic keyring <add|rotate> --id <id> [--keyring <path>]
+/// [--from <file>]`. The credential is read as JSON (from `--from` or stdin) so
+/// no plaintext key ever has to be pasted onto the command line, sealed under the
+/// master passphrase (taken from `$PULLOMATIC_PASSPHRASE`), and written into the
+/// keyring — replacing any existing entry with the same id.
+pub fn subcommand(args: &[String]) -> Result<(), KeyringError> {
+    let action = args.get(0).map(String::as_str);
+    if action != Some("add") && action != Some("rotate") {
+        return Err(KeyringError::Usage(
+            "usage: keyring <add|rotate> --id <id> [--keyring <path>] [--from <file>]".to_owned()));
+    }
+
+    let mut id = None;
+    let mut keyring_path = "pullomatic.keyring".to_owned();
+    let mut from = None;
+
+    let mut rest = args[1..].iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next()
+            .ok_or_else(|| KeyringError::Usage(format!("missing value for {}", flag)))?;
+        match flag.as_str() {
+            "--id" => id = Some(value.clone()),
+            "--keyring" => keyring_path = value.clone(),
+            "--from" => from = Some(value.clone()),
+            other => return Err(KeyringError::Usage(format!("unknown flag {}", other))),
+        }
+    }
+
+    let id = id.ok_or_else(|| KeyringError::Usage("missing --id".to_owned()))?;
+
+    let passphrase = env::var("PULLOMATIC_PASSPHRASE")
+        .map_err(|_| KeyringError::Usage("set $PULLOMATIC_PASSPHRASE to unlock the keyring".to_owned()))?;
+
+    // Read the credential JSON from the named file, or stdin if none was given.
+    let mut json = String::new();
+    match from {
+        Some(path) => json = fs::read_to_string(path)?,
+        None => { io::stdin().read_to_string(&mut json)?; }
+    }
+    let credentials: Credentials = serde_json::from_str(&json)?;
+
+    add_or_rotate(&keyring_path, &passphrase, id.clone(), &credentials)?;
+    println!("Stored credential '{}' in {}", id, keyring_path);
+    return Ok(());
+}
+
+/// Derive a 32-byte AES key from the passphrase and salt using bcrypt-pbkdf.
+///
+/// `rounds` comes off the on-disk sealed entry, so a corrupt or tampered file can
+/// carry `0` (or any invalid value); that is mapped to `KeyringError::Crypto`
+/// rather than panicking and taking the daemon down.
+fn derive_key(passphrase: &str, salt: &[u8], rounds: u32) -> Result<[u8; 32], KeyringError> {
+    let mut key = [0u8; 32];
+    bcrypt_pbkdf::bcrypt_pbkdf(passphrase.as_bytes(), salt, rounds, &mut key)
+        .map_err(|_| KeyringError::Crypto)?;
+    return Ok(key);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyringError, SealedCredential};
+
+    #[test]
+    fn seal_then_unseal_round_trips() {
+        let sealed = SealedCredential::seal_bytes("id".to_owned(), b"a secret key", "passphrase").unwrap();
+        let plaintext = sealed.unseal_bytes("passphrase").unwrap();
+        assert_eq!(plaintext, b"a secret key");
+    }
+
+    #[test]
+    fn wrong_passphrase_yields_crypto_error() {
+        let sealed = SealedCredential::seal_bytes("id".to_owned(), b"a secret key", "passphrase").unwrap();
+        match sealed.unseal_bytes("wrong") {
+            Err(KeyringError::Crypto) => {}
+            other => panic!("expected Crypto, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tampered_ciphertext_yields_crypto_error() {
+        let mut sealed = SealedCredential::seal_bytes("id".to_owned(), b"a secret key", "passphrase").unwrap();
+        sealed.ciphertext[0] ^= 0xff;
+        match sealed.unseal_bytes("passphrase") {
+            Err(KeyringError::Crypto) => {}
+            other => panic!("expected Crypto, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn zero_rounds_does_not_panic() {
+        // A corrupt on-disk entry can carry rounds = 0; it must map to Crypto.
+        let mut sealed = SealedCredential::seal_bytes("id".to_owned(), b"x", "passphrase").unwrap();
+        sealed.rounds = 0;
+        match sealed.unseal_bytes("passphrase") {
+            Err(KeyringError::Crypto) => {}
+            other => panic!("expected Crypto, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use config::Notifier;
+use repo::Repo;
+use std::error;
+use std::fmt;
+use std::io;
+use std::process::Command;
+
+/// An error raised while running a repo's post-update notifiers.
+///
+/// Modelled on `UpdateError`: it carries enough to log *why* a notification
+/// failed without aborting the worker, since a broken hook must not stop the
+/// daemon from servicing other repos.
+#[derive(Debug)]
+pub enum NotifyError {
+    Io(io::Error),
+    Command(i32),
+    Http(u16),
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            NotifyError::Io(ref err) => write!(f, "IO error: {}", err),
+            NotifyError::Command(code) => write!(f, "command exited with status {}", code),
+            NotifyError::Http(status) => write!(f, "POST returned HTTP {}", status),
+        }
+    }
+}
+
+impl error::Error for NotifyError {
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match *self {
+            NotifyError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for NotifyError {
+    fn from(err: io::Error) -> Self { NotifyError::Io(err) }
+}
+
+/// Run every notifier configured for `repo` after a successful change.
+///
+/// Called from `Repo::update` on the worker thread just before it returns
+/// `Ok(true)` — i.e. only when the working tree was hard-reset to a new remote
+/// OID. Each action's failure is logged per repo and the remaining actions still
+/// run, so one bad hook neither hides the others nor blocks the ticker.
+pub fn run(repo: &Repo, old_oid: Option<&str>, new_oid: &str) {
+    for notifier in repo.config().notifiers() {
+        let result = match *notifier {
+            Notifier::Command { ref command, ref args } =>
+                run_command(repo, command, args, old_oid, new_oid),
+
+            Notifier::Webhook { ref url } =>
+                post(repo, url, old_oid, new_oid),
+        };
+
+        if let Err(err) = result {
+            eprintln!("[{}] Notifier failed: {}", repo.name(), err);
+        }
+    }
+}
+
+fn run_command(repo: &Repo,
+               command: &str,
+               args: &[String],
+               old_oid: Option<&str>,
+               new_oid: &str) -> Result<(), NotifyError> {
+    let status = Command::new(command)
+        .args(args)
+        .env("PULLOMATIC_REPO", repo.name())
+        .env("PULLOMATIC_OLD_OID", old_oid.unwrap_or(""))
+        .env("PULLOMATIC_NEW_OID", new_oid)
+        .status()?;
+
+    if !status.success() {
+        return Err(NotifyError::Command(status.code().unwrap_or(-1)));
+    }
+
+    return Ok(());
+}
+
+fn post(repo: &Repo,
+        url: &str,
+        old_oid: Option<&str>,
+        new_oid: &str) -> Result<(), NotifyError> {
+    let payload = json!({
+        "repo": repo.name(),
+        "old_oid": old_oid,
+        "new_oid": new_oid,
+    });
+
+    let response = reqwest::blocking::Client::new()
+        .post(url)
+        .json(&payload)
+        .send()
+        .map_err(|err| NotifyError::Io(io::Error::new(io::ErrorKind::Other, err)))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(NotifyError::Http(status.as_u16()));
+    }
+
+    return Ok(());
+}
@@ -1,18 +1,94 @@
-use config::{Config, Credentials};
+use cli;
+use config::{Backend, Config, Credentials};
 use git2;
+use std::cell::Cell;
 use std::error;
 use std::fmt;
 use std::fs::{self, File};
 use std::io::{self, Read};
 use std::path::Path;
-use std::sync::Mutex;
-use std::time::Instant;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+use std::time::SystemTime;
+
+use dbctx::{DbCtx, StoredState};
+use keyring::UnlockedKeyring;
+use notify;
+
+
+/// Bookkeeping for the credentials callback.
+///
+/// libgit2 keeps invoking the callback until it is handed a credential that
+/// authenticates (or the callback errors), so every method we know about must be
+/// offered at most once — otherwise a rejected credential is retried forever.
+struct CredentialAttempt {
+    tried: Cell<git2::CredentialType>,
+    failed: Cell<bool>,
+}
+
+impl CredentialAttempt {
+    fn new() -> Self {
+        return Self {
+            tried: Cell::new(git2::CredentialType::empty()),
+            failed: Cell::new(false),
+        };
+    }
+
+    /// Returns `true` the first time `method` is offered and marks it as tried.
+    fn try_once(&self, method: git2::CredentialType) -> bool {
+        if self.tried.get().contains(method) {
+            return false;
+        }
 
+        self.tried.set(self.tried.get() | method);
+        return true;
+    }
+
+    /// Record that the callback ran out of methods and refused to authenticate.
+    fn mark_failed(&self) {
+        self.failed.set(true);
+    }
+
+    /// Whether the callback exhausted every method without authenticating. The
+    /// error libgit2 propagates for a callback failure is `Generic`, so this flag —
+    /// not the error class — is what tells an auth failure from a transport error.
+    fn failed(&self) -> bool {
+        return self.failed.get();
+    }
+}
 
 #[derive(Debug)]
 struct RepoState {
-    last_checked: Option<Instant>,
-    last_changed: Option<Instant>,
+    last_checked: Option<SystemTime>,
+    last_changed: Option<SystemTime>,
+    remote_oid: Option<String>,
+    previous_oid: Option<String>,
+}
+
+/// A machine-readable view of a fetch, forwarded over the optional progress
+/// channel so a UI or log aggregator can follow what `update()` is doing.
+#[derive(Debug, Clone)]
+pub enum ProgressNotification {
+    /// A fetch has begun for the repo this channel is attached to.
+    Start,
+
+    /// A throttled tick carrying libgit2's transfer counters.
+    Transfer {
+        received_objects: usize,
+        indexed_objects: usize,
+        total_objects: usize,
+        received_bytes: usize,
+    },
+
+    /// The fetch of the configured ref failed for a reason other than
+    /// authentication, with the error message libgit2 reported.
+    Rejected {
+        reference: String,
+        message: String,
+    },
+
+    /// The fetch finished (successfully or not — see `update()`'s result).
+    Done,
 }
 
 #[derive(Debug)]
@@ -21,12 +97,17 @@ pub struct Repo {
     config: Config,
 
     state: Mutex<RepoState>,
+    progress: Mutex<Option<Sender<ProgressNotification>>>,
+    store: Mutex<Option<Arc<DbCtx>>>,
+    keyring: Mutex<Option<Arc<UnlockedKeyring>>>,
 }
 
 #[derive(Debug)]
 pub enum UpdateError {
     Git(git2::Error),
     Io(io::Error),
+    Auth,
+    Cli(String),
 }
 
 impl fmt::Display for UpdateError {
@@ -34,6 +115,8 @@ impl fmt::Display for UpdateError {
         match *self {
             UpdateError::Git(ref err) => write!(f, "GIT error: {}", err),
             UpdateError::Io(ref err) => write!(f, "IO error: {}", err),
+            UpdateError::Auth => write!(f, "all authentication methods failed"),
+            UpdateError::Cli(ref msg) => write!(f, "git CLI error: {}", msg),
         }
     }
 }
@@ -43,6 +126,8 @@ impl error::Error for UpdateError {
         match *self {
             UpdateError::Git(ref err) => Some(err),
             UpdateError::Io(ref err) => Some(err),
+            UpdateError::Auth => None,
+            UpdateError::Cli(_) => None,
         }
     }
 }
@@ -64,12 +149,173 @@ impl Repo {
             state: Mutex::new(RepoState {
                 last_checked: None,
                 last_changed: None,
+                remote_oid: None,
+                previous_oid: None,
             }),
+            progress: Mutex::new(None),
+            store: Mutex::new(None),
+            keyring: Mutex::new(None),
+        };
+    }
+
+    /// Attach the unlocked keyring so credentials referenced by id in `Config`
+    /// can be resolved without any plaintext secret living in the config file.
+    pub fn set_keyring(&self, keyring: Arc<UnlockedKeyring>) {
+        *self.keyring.lock().unwrap() = Some(keyring);
+    }
+
+    /// Resolve the credentials for this repo, preferring a keyring entry named by
+    /// `config.credential_id()` and falling back to any inline `config.credentials`.
+    fn resolve_credentials(&self) -> Option<Credentials> {
+        if let Some(id) = self.config.credential_id() {
+            if let Some(ref keyring) = *self.keyring.lock().unwrap() {
+                if let Some(credentials) = keyring.get(id) {
+                    return Some(credentials.clone());
+                }
+            }
+        }
+
+        return self.config.credentials.clone();
+    }
+
+    /// Attach the persistent store and hydrate the in-memory state from it.
+    ///
+    /// Called once at startup so a restart does not forget when each repo was
+    /// last checked and re-fetch everything immediately.
+    pub fn set_store(&self, db: Arc<DbCtx>) {
+        if let Ok(Some(stored)) = db.load(&self.name) {
+            let mut state = self.state.lock().unwrap();
+            state.last_checked = stored.last_checked;
+            state.last_changed = stored.last_changed;
+            state.remote_oid = stored.remote_oid;
+        }
+
+        *self.store.lock().unwrap() = Some(db);
+    }
+
+    /// Write the current state back to the store, if one is attached.
+    fn persist(&self) {
+        let db = match *self.store.lock().unwrap() {
+            Some(ref db) => db.clone(),
+            None => return,
+        };
+
+        let stored = {
+            let state = self.state.lock().unwrap();
+            StoredState {
+                last_checked: state.last_checked,
+                last_changed: state.last_changed,
+                remote_oid: state.remote_oid.clone(),
+            }
         };
+
+        if let Err(err) = db.store(&self.name, &stored) {
+            eprintln!("[{}] Could not persist state: {}", self.name, err);
+        }
+    }
+
+    /// Attach a channel that receives `ProgressNotification`s during `update()`.
+    ///
+    /// Optional: with no consumer attached the daemon behaves exactly as before.
+    pub fn set_progress_sender(&self, sender: Sender<ProgressNotification>) {
+        *self.progress.lock().unwrap() = Some(sender);
+    }
+
+    /// Send a notification if a consumer is attached, dropping it silently if the
+    /// receiver has gone away — progress reporting must never fail a fetch.
+    fn notify(&self, notification: ProgressNotification) {
+        if let Some(ref sender) = *self.progress.lock().unwrap() {
+            let _ = sender.send(notification);
+        }
     }
 
+    /// Fetch the remote and hard-reset the working tree to it, returning whether
+    /// the tree actually changed.
+    ///
+    /// The actual fetch/reset is delegated to a backend selected per repo in
+    /// `Config`: the in-process libgit2 implementation (the default) or the
+    /// system `git` CLI, for remotes that only behave under the real binary.
     pub fn update(&self) -> Result<bool, UpdateError> {
-        let now = Some(Instant::now());
+        let result = match self.config.backend() {
+            Backend::Cli => self.update_cli(),
+            Backend::LibGit2 => self.update_libgit2(),
+        };
+
+        // The success branches persist as part of recording the new OID; make sure
+        // a failed fetch still writes `last_checked` too, otherwise a repo whose
+        // fetches keep failing would re-fetch on every restart (its timestamp never
+        // reaching the store) — exactly what the persistence layer set out to stop.
+        if result.is_err() {
+            self.persist();
+        }
+
+        return result;
+    }
+
+    /// Drive fetch/reset through the system `git` binary.
+    ///
+    /// Non-interactive credentials are delivered through a throwaway `GIT_ASKPASS`
+    /// helper that echoes the configured secret, with `GIT_TERMINAL_PROMPT=0` so
+    /// `git` never blocks on a TTY. Exit status and captured output are mapped into
+    /// `UpdateError::Cli`.
+    fn update_cli(&self) -> Result<bool, UpdateError> {
+        let now = Some(SystemTime::now());
+        self.state.lock().unwrap().last_checked = now;
+
+        let path = Path::new(&self.config.path);
+        if path.exists() {
+            println!("[{}] Using existing repository", self.name);
+        } else {
+            println!("[{}] Initialized new repository", self.name);
+            fs::create_dir_all(path)?;
+            cli::git(path, &self.config, &["init"])?;
+        }
+
+        let askpass = cli::Askpass::new(&self.config)?;
+
+        println!("[{}] Fetching data from remote", self.name);
+        self.notify(ProgressNotification::Start);
+        let fetch = cli::git_with_askpass(path, &self.config, askpass.as_ref(), &[
+            "fetch",
+            "--prune",
+            &self.config.remote_url,
+            &format!("+{}:refs/pullomatic", self.config.remote_ref()),
+        ]);
+        self.notify(ProgressNotification::Done);
+        fetch?;
+        println!("[{}] Fetched data from remote", self.name);
+
+        let remote_oid = cli::git(path, &self.config, &["rev-parse", "refs/pullomatic"])?
+            .trim().to_owned();
+        let latest_oid = cli::git(path, &self.config, &["rev-parse", "HEAD"])
+            .ok().map(|oid| oid.trim().to_owned());
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.remote_oid = Some(remote_oid.clone());
+            state.previous_oid = latest_oid.clone();
+        }
+
+        if latest_oid.as_ref() == Some(&remote_oid) {
+            println!("[{}] Already up to date", self.name);
+            self.persist();
+            return Ok(false);
+        }
+
+        cli::git(path, &self.config, &["reset", "--hard", "refs/pullomatic"])?;
+        cli::git(path, &self.config, &["clean", "-fd"])?;
+
+        println!("[{}] Updated to {}", self.name, remote_oid);
+        self.state.lock().unwrap().last_changed = now;
+        self.persist();
+
+        notify::run(self, latest_oid.as_deref(), &remote_oid);
+
+        return Ok(true);
+    }
+
+    fn update_libgit2(&self) -> Result<bool, UpdateError> {
+        let now = Some(SystemTime::now());
 
         self.state.lock().unwrap().last_checked = now;
 
@@ -91,6 +337,17 @@ impl Repo {
 
         let mut remote = repository.remote_anonymous(&self.config.remote_url)?;
 
+        // libgit2 calls the credentials callback repeatedly and loops forever if it
+        // keeps being handed the same credential, so we track which methods have
+        // already been offered and hand out each one at most once; no separate
+        // attempt counter is kept, the set of tried methods alone bounds the
+        // retries. This mirrors the resolution order used by Cargo's
+        // `with_authentication`. The state is kept in a `Cell`/set captured by the
+        // closure and inspected after the fetch to tell an authentication failure
+        // apart from an unrelated transport error.
+        let attempt = CredentialAttempt::new();
+        let credentials = self.resolve_credentials();
+
         let mut remote_cb = git2::RemoteCallbacks::new();
         remote_cb.credentials(|url, username, allowed| {
             println!("[{}] cred: url = {:?}", self.name, url);
@@ -98,7 +355,7 @@ impl Repo {
             println!("[{}] cred: allowed = {:?}", self.name, allowed);
 
             if allowed.contains(git2::CredentialType::USERNAME) {
-                match self.config.credentials {
+                match credentials {
                     Some(Credentials::SSH(ref ssh)) => if let Some(ref username) = ssh.username {
                         return git2::Cred::username(username);
                     },
@@ -107,56 +364,132 @@ impl Repo {
                         return git2::Cred::username(username);
                     },
 
-                    None => return Err(git2::Error::from_str("Authentication is required"))
+                    None => {}
                 }
             }
 
-            if allowed.contains(git2::CredentialType::SSH_MEMORY) {
-                if let Some(Credentials::SSH(ref ssh)) = self.config.credentials {
-                    let private_key = if ssh.private_key_path {
-                        let path = ssh.private_key.clone();
-                        let mut file = File::open(path).map_err(|_| git2::Error::from_str("Could not open credentials file"))?;
-                        let mut contents = String::new();
-                        file.read_to_string(&mut contents).map_err(|_| git2::Error::from_str("Could not read credentials file"))?;
-                        contents
-                    } else {
-                        ssh.private_key.clone()
-                    };
-
-                    return git2::Cred::ssh_key_from_memory(username.unwrap(),
-                                                           ssh.public_key.as_ref().map(String::as_ref),
-                                                           private_key.as_ref(),
-                                                           ssh.passphrase.as_ref().map(String::as_ref));
+            if allowed.contains(git2::CredentialType::SSH_KEY) {
+                let username = username.unwrap_or("git");
+
+                // Prefer a running ssh-agent before falling back to in-memory keys,
+                // offering each exactly once so a rejected key does not loop.
+                if attempt.try_once(git2::CredentialType::SSH_KEY) {
+                    return git2::Cred::ssh_key_from_agent(username);
+                }
+
+                if let Some(Credentials::SSH(ref ssh)) = credentials {
+                    if attempt.try_once(git2::CredentialType::SSH_MEMORY) {
+                        let private_key = if ssh.private_key_path {
+                            let path = ssh.private_key.clone();
+                            let mut file = File::open(path).map_err(|_| git2::Error::from_str("Could not open credentials file"))?;
+                            let mut contents = String::new();
+                            file.read_to_string(&mut contents).map_err(|_| git2::Error::from_str("Could not read credentials file"))?;
+                            contents
+                        } else {
+                            ssh.private_key.clone()
+                        };
+
+                        return git2::Cred::ssh_key_from_memory(username,
+                                                               ssh.public_key.as_ref().map(String::as_ref),
+                                                               private_key.as_ref(),
+                                                               ssh.passphrase.as_ref().map(String::as_ref));
+                    }
                 }
             }
 
-            if allowed.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
-                if let Some(Credentials::Password(ref password)) = self.config.credentials {
-                    return git2::Cred::userpass_plaintext(username.unwrap(),
+            if allowed.contains(git2::CredentialType::USER_PASS_PLAINTEXT)
+                && attempt.try_once(git2::CredentialType::USER_PASS_PLAINTEXT) {
+                if let Some(Credentials::Password(ref password)) = credentials {
+                    return git2::Cred::userpass_plaintext(username.unwrap_or(""),
                                                           password.password.as_ref());
                 }
+
+                // No credentials configured: fall back to the user's git config
+                // credential helper, exactly like a plain `git` invocation would.
+                let cfg = git2::Config::open_default()?;
+                return git2::Cred::credential_helper(&cfg, url, username);
+            }
+
+            attempt.mark_failed();
+            return Err(git2::Error::from_str("all authentication methods failed"));
+        });
+
+        // Forward libgit2's transfer counters as structured events, throttled so a
+        // busy fetch does not flood the channel: we only emit when the received
+        // object count advances by at least a step, or once everything is in.
+        let last_reported = Cell::new(0usize);
+        remote_cb.transfer_progress(|stats| {
+            let received = stats.received_objects();
+            let all_in = received == stats.total_objects();
+            if (all_in && received != last_reported.get()) || received >= last_reported.get() + 64 {
+                last_reported.set(received);
+                self.notify(ProgressNotification::Transfer {
+                    received_objects: received,
+                    indexed_objects: stats.indexed_objects(),
+                    total_objects: stats.total_objects(),
+                    received_bytes: stats.received_bytes(),
+                });
             }
+            return true;
+        });
 
-            return Err(git2::Error::from_str("Unsupported authentication"));
+        // Log every ref the fetch actually moves to stdout; these tip updates are
+        // not sent over the progress channel.
+        remote_cb.update_tips(|reference, from, to| {
+            println!("[{}] {} {} -> {}", self.name, reference, from, to);
+            return true;
         });
 
         println!("[{}] Fetching data from remote", self.name);
-        remote.fetch(&[&format!("+{}:refs/pullomatic", self.config.remote_ref())],
+        self.notify(ProgressNotification::Start);
+        let fetch_result = remote.fetch(&[&format!("+{}:refs/pullomatic", self.config.remote_ref())],
                      Some(git2::FetchOptions::new()
                              .prune(git2::FetchPrune::On)
                              .remote_callbacks(remote_cb)),
-                     None)?;
+                     None);
+
+        if fetch_result.is_err() {
+            // If the credentials callback ran out of methods, surface the dedicated
+            // variant rather than the raw libgit2 message. `failed()` is the
+            // authoritative signal — libgit2 re-invokes the callback on a real auth
+            // rejection until it hits `mark_failed`, whereas the error class cannot
+            // tell a pre-auth failure from a post-auth transport error (e.g. an
+            // HTTPS reset after a helper cred already authenticated).
+            if attempt.failed() {
+                self.notify(ProgressNotification::Done);
+                return Err(UpdateError::Auth);
+            }
+
+            // Not an auth failure: surface why the fetch stalled so a consumer can
+            // see the reason instead of a silent lack of progress.
+            if let Some(err) = fetch_result.as_ref().err() {
+                self.notify(ProgressNotification::Rejected {
+                    reference: self.config.remote_ref().to_owned(),
+                    message: err.message().to_owned(),
+                });
+            }
+        }
+        self.notify(ProgressNotification::Done);
+        fetch_result?;
         println!("[{}] Fetched data from remote", self.name);
 
 //        repository.find_reference("HEAD")?;
         let latest_obj = repository.revparse_single("HEAD").ok();
         let remote_obj = repository.revparse_single("refs/pullomatic")?;
 
-        if let Some(ref latest_obj) = latest_obj {
-            if latest_obj.id() == remote_obj.id() {
-                println!("[{}] Already up to date", self.name);
-                return Ok(false);
-            }
+        let new_oid = remote_obj.id().to_string();
+        let old_oid = latest_obj.as_ref().map(|obj| obj.id().to_string());
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.remote_oid = Some(new_oid.clone());
+            state.previous_oid = old_oid.clone();
+        }
+
+        if old_oid.as_ref() == Some(&new_oid) {
+            println!("[{}] Already up to date", self.name);
+            self.persist();
+            return Ok(false);
         }
 
         repository.reset(&remote_obj,
@@ -165,15 +498,60 @@ impl Repo {
                                  .force()
                                  .remove_untracked(true)))?;
 
-        println!("[{}] Updated to {}", self.name, remote_obj.id());
+        println!("[{}] Updated to {}", self.name, new_oid);
         self.state.lock().unwrap().last_changed = now;
+        self.persist();
+
+        // Fire the post-update notifiers on this (worker) thread; failures are
+        // logged per repo and never propagate back to the ticker.
+        notify::run(self, old_oid.as_deref(), &new_oid);
 
         return Ok(true);
     }
 
     pub fn name(&self) -> &str { &self.name }
 
+    /// The remote OID resolved by the last `update()`, if one has run.
+    pub fn remote_oid(&self) -> Option<String> { self.state.lock().unwrap().remote_oid.clone() }
+
+    /// The OID `HEAD` was on when the last successful fetch completed (before any
+    /// reset), if one has run in this process; it is not persisted.
+    pub fn previous_oid(&self) -> Option<String> { self.state.lock().unwrap().previous_oid.clone() }
+
     pub fn config(&self) -> &Config { &self.config }
 
-    pub fn last_checked(&self) -> Option<Instant> { self.state.lock().unwrap().last_checked }
+    pub fn last_checked(&self) -> Option<SystemTime> { self.state.lock().unwrap().last_checked }
+
+    /// When this repo was last fetched, as wall-clock time surviving a restart.
+    /// The ticker uses this to decide whether an update is outstanding.
+    pub fn last_updated(&self) -> Option<SystemTime> { self.state.lock().unwrap().last_checked }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::CredentialAttempt;
+    use git2::CredentialType;
+
+    #[test]
+    fn offers_each_method_once() {
+        let attempt = CredentialAttempt::new();
+
+        // Each method is offered the first time and refused on every repeat, so
+        // libgit2 cannot be handed the same credential in an infinite loop.
+        assert!(attempt.try_once(CredentialType::SSH_KEY));
+        assert!(!attempt.try_once(CredentialType::SSH_KEY));
+
+        assert!(attempt.try_once(CredentialType::USER_PASS_PLAINTEXT));
+        assert!(!attempt.try_once(CredentialType::USER_PASS_PLAINTEXT));
+    }
+
+    #[test]
+    fn failed_flag_defaults_false_and_latches() {
+        let attempt = CredentialAttempt::new();
+        assert!(!attempt.failed());
+
+        attempt.mark_failed();
+        assert!(attempt.failed());
+    }
 }
@@ -2,7 +2,7 @@ use repo::Repo;
 use std::sync::{Arc, atomic::Ordering};
 use std::sync::mpsc::SyncSender;
 use std::thread::{self, JoinHandle};
-use std::time::{Duration, Instant};
+use std::time::{Duration, SystemTime};
 
 pub fn ticker(repo: Arc<Repo>,
               producer: SyncSender<Arc<Repo>>) -> Option<JoinHandle<()>> {
@@ -16,8 +16,10 @@ pub fn ticker(repo: Arc<Repo>,
             while RUNNING.load(Ordering::SeqCst) {
                 // TODO: Calculate sleep time instead of checking regulary
 
-                // Check if update is outstanding and send it as task to the worker
-                if repo.last_updated().map_or(true, |t| t + interval < Instant::now()) {
+                // Check if update is outstanding and send it as task to the worker.
+                // The timestamp is persisted wall-clock time, so the interval is
+                // honored across daemon restarts rather than reset to "never checked".
+                if repo.last_updated().map_or(true, |t| t + interval < SystemTime::now()) {
                     producer.send(repo.clone()).unwrap();
                 }
 
@@ -0,0 +1,216 @@
+use repo::Repo;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use std::io::Read;
+use std::sync::{Arc, atomic::Ordering};
+use std::sync::mpsc::SyncSender;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tiny_http::{Method, Response, Server};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Largest webhook body we will read. Forge push payloads are a few KiB; cap the
+/// read so a hostile or broken client cannot exhaust memory.
+const MAX_BODY: u64 = 1024 * 1024;
+
+/// Spawn the webhook listener bound to `addr` (taken from `Config`).
+///
+/// Unlike `ticker`, which is one thread per repo, this is a single HTTP server
+/// shared by every repo that declares a `webhook` section. A push event is
+/// routed to a repo by matching the request path against `webhook.path`; the
+/// request is only enqueued once the HMAC signature verifies and the pushed ref
+/// matches the webhook section's own `remote_ref`, so it reuses the same
+/// worker/`update()` path as the ticker.
+pub fn webhook(repos: Vec<Arc<Repo>>,
+               addr: String,
+               producer: SyncSender<Arc<Repo>>) -> Option<JoinHandle<()>> {
+    // Only stand the server up if at least one repo listens for webhooks.
+    if !repos.iter().any(|repo| repo.config().webhook().is_some()) {
+        return None;
+    }
+
+    let server = match Server::http(&addr) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("[webhook] Could not bind {}: {}", addr, err);
+            return None;
+        }
+    };
+
+    return Some(thread::spawn(move || {
+        use super::RUNNING;
+        println!("[webhook] Listening on {}", addr);
+
+        while RUNNING.load(Ordering::SeqCst) {
+            // Poll with a timeout rather than blocking indefinitely, so the thread
+            // notices a shutdown within a second like the ticker does.
+            let mut request = match server.recv_timeout(Duration::from_secs(1)) {
+                Ok(Some(request)) => request,
+                Ok(None) => continue,
+                Err(err) => {
+                    eprintln!("[webhook] recv error: {}", err);
+                    continue;
+                }
+            };
+
+            if *request.method() != Method::Post {
+                let _ = request.respond(Response::empty(405));
+                continue;
+            }
+
+            // Find the repo whose configured path matches this request.
+            let url = request.url().to_owned();
+            let repo = repos.iter().find(|repo| {
+                repo.config().webhook().map_or(false, |hook| hook.path == url)
+            }).cloned();
+
+            let repo = match repo {
+                Some(repo) => repo,
+                None => {
+                    let _ = request.respond(Response::empty(404));
+                    continue;
+                }
+            };
+
+            // Pull the declared secret and the signature header out before reading
+            // the body so a malformed request is rejected cheaply.
+            let hook = repo.config().webhook().unwrap();
+            let signature = request.headers().iter()
+                .find(|h| {
+                    let field = h.field.as_str().as_str();
+                    field.eq_ignore_ascii_case("X-Hub-Signature-256")
+                        || field.eq_ignore_ascii_case("X-Gitea-Signature")
+                        || field.eq_ignore_ascii_case("X-Forgejo-Signature")
+                })
+                .map(|h| h.value.as_str().to_owned());
+
+            if signature.is_none() {
+                println!("[{}] Rejected webhook with no signature", repo.name());
+                let _ = request.respond(Response::empty(401));
+                continue;
+            }
+
+            let mut body = Vec::new();
+            if request.as_reader().take(MAX_BODY).read_to_end(&mut body).is_err() {
+                let _ = request.respond(Response::empty(400));
+                continue;
+            }
+
+            if !verify_signature(hook.secret.as_bytes(), &body, signature.as_deref()) {
+                println!("[{}] Rejected webhook with bad signature", repo.name());
+                let _ = request.respond(Response::empty(401));
+                continue;
+            }
+
+            if !ref_matches(&body, &hook.remote_ref) {
+                // A valid delivery, but for a ref we do not track — acknowledge and
+                // move on without enqueueing work.
+                let _ = request.respond(Response::empty(204));
+                continue;
+            }
+
+            println!("[{}] Webhook push accepted, enqueueing update", repo.name());
+            producer.send(repo.clone()).unwrap();
+            let _ = request.respond(Response::empty(202));
+        }
+    }));
+}
+
+/// Verify the HMAC-SHA256 signature header against the raw body.
+///
+/// GitHub sends `sha256=<hex>`; Gitea/Forgejo send the bare hex digest. Both are
+/// accepted. The comparison runs through `Mac::verify_slice` so it is constant
+/// time.
+fn verify_signature(secret: &[u8], body: &[u8], signature: Option<&str>) -> bool {
+    let signature = match signature {
+        Some(signature) => signature.trim_start_matches("sha256="),
+        None => return false,
+    };
+
+    let digest = match hex_decode(signature) {
+        Some(digest) => digest,
+        None => return false,
+    };
+
+    let mut mac = match HmacSha256::new_from_slice(secret) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(body);
+    return mac.verify_slice(&digest).is_ok();
+}
+
+/// Check that the push payload's `ref` field matches the ref we fetch.
+fn ref_matches(body: &[u8], remote_ref: &str) -> bool {
+    let payload: Value = match serde_json::from_slice(body) {
+        Ok(payload) => payload,
+        Err(_) => return false,
+    };
+
+    return payload.get("ref").and_then(Value::as_str) == Some(remote_ref);
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+
+    return (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ref_matches, verify_signature};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    fn sign(secret: &[u8], body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(body);
+        let digest = mac.finalize().into_bytes();
+        let mut hex = String::new();
+        for byte in digest {
+            hex.push_str(&format!("{:02x}", byte));
+        }
+        return format!("sha256={}", hex);
+    }
+
+    #[test]
+    fn accepts_a_valid_signature() {
+        let secret = b"s3cr3t";
+        let body = br#"{"ref":"refs/heads/master"}"#;
+        let signature = sign(secret, body);
+
+        assert!(verify_signature(secret, body, Some(&signature)));
+    }
+
+    #[test]
+    fn rejects_a_tampered_body_or_missing_header() {
+        let secret = b"s3cr3t";
+        let body = br#"{"ref":"refs/heads/master"}"#;
+        let signature = sign(secret, body);
+
+        // Same signature, body altered (the ref upper-cased).
+        let tampered = br#"{"ref":"refs/heads/MASTER"}"#;
+        assert!(!verify_signature(secret, tampered, Some(&signature)));
+
+        // Right body, wrong secret.
+        assert!(!verify_signature(b"other", body, Some(&signature)));
+
+        // No signature header at all.
+        assert!(!verify_signature(secret, body, None));
+    }
+
+    #[test]
+    fn ref_matches_only_the_configured_ref() {
+        let body = br#"{"ref":"refs/heads/master"}"#;
+        assert!(ref_matches(body, "refs/heads/master"));
+        assert!(!ref_matches(body, "refs/heads/develop"));
+    }
+}